@@ -1,6 +1,11 @@
+#[cfg(all(feature = "streams", any(feature = "functions", feature = "tools")))]
+use std::collections::BTreeMap;
 use std::fmt;
+use std::path::Path;
 
-#[cfg(feature = "functions")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+#[cfg(any(feature = "functions", feature = "tools"))]
 use crate::functions::FunctionCall;
 use serde::{de, Deserialize, Serialize};
 
@@ -19,6 +24,8 @@ pub enum Role {
     User,
     /// A message related to ChatGPT functions. Does not have much use without the `functions` feature.
     Function,
+    /// A message containing the result of a tool call. Does not have much use without the `tools` feature.
+    Tool,
 }
 
 /// Type of the message content
@@ -39,10 +46,21 @@ pub struct ChatMessage {
     /// Actual content of the message
     #[serde(deserialize_with = "string_or_array")]
     pub content: Vec<ChatMessageContent>,
+    /// An optional name to disambiguate messages from multiple users/tools with the same role
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     /// Function call (if present)
     #[cfg(feature = "functions")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub function_call: Option<FunctionCall>,
+    /// The tool calls requested by the model (if present)
+    #[cfg(feature = "tools")]
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// The ID of the tool call this message is a result of (only used for `Role::Tool` messages)
+    #[cfg(feature = "tools")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 impl ChatMessage {
@@ -51,11 +69,23 @@ impl ChatMessage {
         Self {
             role,
             content: content.into(),
+            name: None,
             #[cfg(feature = "functions")]
             function_call: None,
+            #[cfg(feature = "tools")]
+            tool_calls: Vec::new(),
+            #[cfg(feature = "tools")]
+            tool_call_id: None,
         }
     }
 
+    /// Sets the `name` of this message, used to disambiguate multiple users or to label
+    /// `function`/`tool` result messages
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
     /// Returns concatenated content of the message
     pub fn content(&self) -> String {
         self.content
@@ -73,10 +103,15 @@ impl ChatMessage {
     }
 
     /// Converts multiple response chunks into multiple (or a single) chat messages
+    ///
+    /// Each response index carries streamed text content and/or one or more function/tool calls,
+    /// each assembled from its concatenated argument fragments and keyed by its own call index
+    /// (parallel tool calls use distinct indices; the legacy singular function call is always
+    /// index `0`).
     #[cfg(feature = "streams")]
     pub fn from_response_chunks(chunks: Vec<ResponseChunk>) -> Vec<Self> {
         let mut result: Vec<Self> = Vec::new();
-        let mut responses: Vec<(Role, String)> = Vec::new();
+        let mut responses: Vec<StreamedResponse> = Vec::new();
 
         for chunk in chunks {
             match chunk {
@@ -84,37 +119,130 @@ impl ChatMessage {
                     delta,
                     response_index,
                 } => {
-                    let (_, response) = responses
+                    let response = responses
                         .get_mut(response_index)
                         .expect("Invalid response chunk sequence!");
 
-                    response.push_str(&delta);
+                    response.content.push_str(&delta);
+                }
+                #[cfg(any(feature = "functions", feature = "tools"))]
+                ResponseChunk::FunctionCallDelta {
+                    call_index,
+                    id,
+                    name,
+                    arguments,
+                    response_index,
+                } => {
+                    let response = responses
+                        .get_mut(response_index)
+                        .expect("Invalid response chunk sequence!");
+                    let call = response.calls.entry(call_index).or_default();
+
+                    if id.is_some() {
+                        call.id = id;
+                    }
+                    if name.is_some() {
+                        call.name = name;
+                    }
+                    call.arguments.push_str(&arguments);
                 }
                 ResponseChunk::BeginResponse {
                     role,
                     response_index: _,
                 } => {
-                    responses.push((role, String::with_capacity(16)));
+                    responses.push(StreamedResponse {
+                        role,
+                        content: String::with_capacity(16),
+                        #[cfg(any(feature = "functions", feature = "tools"))]
+                        calls: BTreeMap::new(),
+                    });
 
                     let msg = ChatMessage {
                         role,
                         content: vec![],
+                        name: None,
                         #[cfg(feature = "functions")]
                         function_call: None,
+                        #[cfg(feature = "tools")]
+                        tool_calls: Vec::new(),
+                        #[cfg(feature = "tools")]
+                        tool_call_id: None,
                     };
                     result.push(msg);
                 }
-                _ => {}
+                ResponseChunk::CloseResponse { .. } | ResponseChunk::Done => {}
             }
         }
 
         responses
             .into_iter()
-            .map(|(role, response)| ChatMessage::new(role, &[ChatMessageContent::text(response)]))
+            .map(|response| {
+                #[allow(unused_mut)]
+                let mut msg =
+                    ChatMessage::new(response.role, &[ChatMessageContent::text(response.content)]);
+
+                #[cfg(feature = "tools")]
+                {
+                    msg.tool_calls = response
+                        .calls
+                        .into_values()
+                        .map(|call| ToolCall {
+                            id: call.id.unwrap_or_default(),
+                            r#type: "function".to_string(),
+                            function: FunctionCall {
+                                name: call.name.unwrap_or_default(),
+                                arguments: call.arguments,
+                            },
+                        })
+                        .collect();
+                }
+
+                #[cfg(all(feature = "functions", not(feature = "tools")))]
+                if let Some(call) = response.calls.into_values().next() {
+                    msg.function_call = Some(FunctionCall {
+                        name: call.name.unwrap_or_default(),
+                        arguments: call.arguments,
+                    });
+                }
+
+                msg
+            })
             .collect::<Vec<_>>()
     }
 }
 
+/// Accumulated state for a single in-flight streamed response
+#[cfg(feature = "streams")]
+struct StreamedResponse {
+    role: Role,
+    content: String,
+    /// Function/tool calls being reassembled, keyed by their call index
+    #[cfg(any(feature = "functions", feature = "tools"))]
+    calls: BTreeMap<usize, StreamedCall>,
+}
+
+/// A single function/tool call being reassembled from streamed fragments
+#[cfg(all(feature = "streams", any(feature = "functions", feature = "tools")))]
+#[derive(Default)]
+struct StreamedCall {
+    id: Option<String>,
+    name: Option<String>,
+    arguments: String,
+}
+
+/// A single tool call requested by the model, as part of the modern `tools`/`tool_calls` protocol
+#[cfg(feature = "tools")]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// The ID of this tool call, to be echoed back in the `tool_call_id` of the result message
+    pub id: String,
+    /// The type of tool being called. Currently always `"function"`
+    #[serde(rename = "type")]
+    pub r#type: String,
+    /// The function the model wants to call
+    pub function: FunctionCall,
+}
+
 /// Content of the message
 #[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -133,7 +261,7 @@ impl fmt::Display for ChatMessageContent {
         match self {
             ChatMessageContent::Text { text } => write!(f, "{}", text),
             ChatMessageContent::ImageUrl {
-                image_url: ImageUrlContent { url },
+                image_url: ImageUrlContent { url, .. },
             } => write!(f, "{}", url),
         }
     }
@@ -148,9 +276,57 @@ impl ChatMessageContent {
     /// Creates a new image URL message content
     pub fn image_url(url: impl Into<String>) -> Self {
         Self::ImageUrl {
-            image_url: ImageUrlContent { url: url.into() },
+            image_url: ImageUrlContent {
+                url: url.into(),
+                detail: None,
+            },
         }
     }
+
+    /// Creates a new image URL message content with an explicit detail level
+    pub fn image_url_with_detail(url: impl Into<String>, detail: ImageUrlDetail) -> Self {
+        Self::ImageUrl {
+            image_url: ImageUrlContent {
+                url: url.into(),
+                detail: Some(detail),
+            },
+        }
+    }
+
+    /// Creates a new image message content from a local file, base64-encoding its contents into
+    /// a `data:` URL. If `path` already looks like a URL (starts with `http://`, `https://` or
+    /// `data:`), it is passed through unchanged instead of being read from disk.
+    pub fn image_file(path: impl AsRef<str>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+
+        if path.starts_with("http://") || path.starts_with("https://") || path.starts_with("data:")
+        {
+            return Ok(Self::image_url(path));
+        }
+
+        let bytes = std::fs::read(path)?;
+        let mime = mime_type_from_extension(path);
+        let encoded = STANDARD.encode(bytes);
+
+        Ok(Self::image_url(format!("data:{mime};base64,{encoded}")))
+    }
+}
+
+/// Guesses the MIME type of an image from its file extension, defaulting to
+/// `application/octet-stream` when unknown
+fn mime_type_from_extension(path: &str) -> &'static str {
+    match Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
 }
 
 impl From<String> for ChatMessageContent {
@@ -179,6 +355,22 @@ impl From<&str> for ChatMessageContent {
 #[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct ImageUrlContent {
     url: String,
+    /// How much detail to use when processing this image, as described in the
+    /// [vision guide](https://platform.openai.com/docs/guides/vision/low-or-high-fidelity-image-understanding)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<ImageUrlDetail>,
+}
+
+/// The detail level ChatGPT should use to process an image, trading off token cost for fidelity
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize, Eq, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageUrlDetail {
+    /// Process the image at low resolution, using fewer tokens
+    Low,
+    /// Process the image at high resolution, using more tokens
+    High,
+    /// Let the model decide which detail level to use
+    Auto,
 }
 
 /// A request struct sent to the API to request a message completion
@@ -204,10 +396,88 @@ pub struct CompletionRequest<'a> {
     /// Determines the amount of output responses
     #[serde(rename = "n")]
     pub reply_count: u32,
+    /// Whether to return the log probabilities of the output tokens
+    pub logprobs: bool,
+    /// The number of most likely tokens to return the log probabilities for at each token position.
+    /// `logprobs` must be set to `true` if this parameter is used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
     /// All functions that can be called by ChatGPT
     #[cfg(feature = "functions")]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub functions: &'a Vec<serde_json::Value>,
+    /// All tools that can be called by ChatGPT
+    #[cfg(feature = "tools")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: &'a Vec<serde_json::Value>,
+    /// Controls which (if any) tool is called by the model
+    #[cfg(feature = "tools")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+}
+
+/// A request struct sent to the API to request a legacy, prompt-based text completion.
+/// Prefer [`CompletionRequest`] unless talking to a server that only implements the older
+/// `/v1/completions` endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TextCompletionRequest<'a> {
+    /// The model to be used, currently `gpt-3.5-turbo-instruct`, but may change in future
+    pub model: &'a str,
+    /// The prompt to generate a completion for
+    pub prompt: &'a str,
+    /// Whether the message response should be gradually streamed
+    pub stream: bool,
+    /// The extra randomness of response
+    pub temperature: f32,
+    /// Controls diversity via nucleus sampling, not recommended to use with temperature
+    pub top_p: f32,
+    /// Controls the maximum number of tokens to generate in the completion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Determines how much to penalize new tokens based on their existing frequency so far
+    pub frequency_penalty: f32,
+    /// Determines how much to penalize new tokens pased on their existing presence so far
+    pub presence_penalty: f32,
+    /// Determines the amount of output responses
+    #[serde(rename = "n")]
+    pub reply_count: u32,
+}
+
+/// A response struct received from the API after requesting a legacy text completion
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+pub struct TextCompletionResponse {
+    /// Unique ID of the message, but not in a UUID format.
+    /// Example: `cmpl-6p5FEv1JHictSSnDZsGU4KvbuBsbu`
+    #[serde(rename = "id")]
+    pub message_id: Option<String>,
+    /// Unix seconds timestamp of when the response was created
+    #[serde(rename = "created")]
+    pub created_timestamp: Option<u64>,
+    /// The model that was used for this completion
+    pub model: String,
+    /// Token usage of this completion
+    pub usage: TokenUsage,
+    /// Completion choices for this response, guaranteed to contain at least one choice
+    pub choices: Vec<TextCompletionChoice>,
+}
+
+impl TextCompletionResponse {
+    /// A shortcut to access the completed text of the first choice
+    pub fn text(&self) -> &str {
+        // Unwrap is safe here, as we know that at least one choice is provided
+        &self.choices.first().unwrap().text
+    }
+}
+
+/// A single text completion choice struct
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+pub struct TextCompletionChoice {
+    /// The completed text
+    pub text: String,
+    /// The index of this choice in the outer `choices` array
+    pub index: u32,
+    /// The reason completion was stopped
+    pub finish_reason: FinishReason,
 }
 
 /// Represents a response from the API
@@ -266,9 +536,58 @@ pub struct MessageChoice {
     /// The actual message
     pub message: ChatMessage,
     /// The reason completion was stopped
-    pub finish_reason: String,
+    pub finish_reason: FinishReason,
     /// The index of this message in the outer `message_choices` array
     pub index: u32,
+    /// The log probabilities of the output tokens, present when `logprobs` was requested
+    pub logprobs: Option<LogProbs>,
+}
+
+/// The reason a message completion was stopped
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize, Eq, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// The model reached a natural stopping point, or a provided stop sequence
+    Stop,
+    /// The completion was cut off for reaching `max_tokens` or the token limit
+    Length,
+    /// Content was omitted due to a flag from content filters
+    ContentFilter,
+    /// The model decided to call a function, via the deprecated `functions` API
+    FunctionCall,
+    /// The model decided to call one or more tools, via the `tools` API
+    ToolCalls,
+}
+
+/// The log probabilities of the tokens generated for a single message choice
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+pub struct LogProbs {
+    /// The log probability information for each output token
+    pub content: Vec<TokenLogProb>,
+}
+
+/// Log probability information for a single output token
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+pub struct TokenLogProb {
+    /// The token
+    pub token: String,
+    /// The log probability of this token
+    pub logprob: f32,
+    /// The UTF-8 bytes representation of the token, if it cannot be represented as valid UTF-8
+    pub bytes: Option<Vec<u8>>,
+    /// The most likely tokens at this position and their log probabilities
+    pub top_logprobs: Vec<TopLogProb>,
+}
+
+/// Log probability information for one of the most likely tokens at a given position
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+pub struct TopLogProb {
+    /// The token
+    pub token: String,
+    /// The log probability of this token
+    pub logprob: f32,
+    /// The UTF-8 bytes representation of the token, if it cannot be represented as valid UTF-8
+    pub bytes: Option<Vec<u8>>,
 }
 
 /// The token usage of a specific response
@@ -300,10 +619,33 @@ pub enum ResponseChunk {
         /// Index of the message. Used when `reply_count` is set to more than 1 in API config
         response_index: usize,
     },
-    /// Ends a single message response response
+    /// A fragment of a function/tool call, announcing its name and/or streaming a piece of its
+    /// arguments. Fragments sharing the same `response_index` and `call_index` are concatenated
+    /// to reassemble the complete call; a model may request several calls in parallel within the
+    /// same response, each with its own `call_index`.
+    #[cfg(any(feature = "functions", feature = "tools"))]
+    FunctionCallDelta {
+        /// Index of this call within the response. Always `0` for the legacy singular
+        /// `function_call`; tool calls use their own index to support parallel calls
+        call_index: usize,
+        /// The ID of this tool call, present only in the first fragment. Not set for the legacy
+        /// `function_call`
+        id: Option<String>,
+        /// The name of the function being called, present only in the first fragment
+        name: Option<String>,
+        /// A fragment of the JSON-encoded arguments
+        arguments: String,
+        /// Index of the message. Used when `reply_count` is set to more than 1 in API config
+        response_index: usize,
+    },
+    /// Ends a single message response response. `finish_reason` is part of the public shape of
+    /// this variant, so any other module constructing or matching it (e.g. the SSE stream parser)
+    /// must be updated in lockstep when this struct-variant's fields change.
     CloseResponse {
         /// Index of the message finished. Used when `reply_count` is set to more than 1 in API config
         response_index: usize,
+        /// The reason this response was finished, if known
+        finish_reason: Option<FinishReason>,
     },
     /// Marks end of stream
     Done,
@@ -342,10 +684,45 @@ pub enum InboundChunkPayload {
         /// The part of content
         content: String,
     },
+    /// Announces and/or streams a fragment of the legacy, singular function call
+    #[cfg(any(feature = "functions", feature = "tools"))]
+    FunctionCall {
+        /// The function call fragment
+        function_call: InboundFunctionCallDelta,
+    },
+    /// Announces and/or streams fragments of one or more (possibly parallel) tool calls
+    #[cfg(feature = "tools")]
+    ToolCalls {
+        /// The tool call fragments present in this chunk
+        tool_calls: Vec<InboundToolCallDelta>,
+    },
     /// Closes a single message
     Close {},
 }
 
+/// A single fragment of a streamed function call
+#[derive(Debug, Clone, Deserialize)]
+#[cfg(all(feature = "streams", any(feature = "functions", feature = "tools")))]
+pub struct InboundFunctionCallDelta {
+    /// The name of the function being called, present only in the first fragment
+    pub name: Option<String>,
+    /// A fragment of the JSON-encoded arguments
+    #[serde(default)]
+    pub arguments: String,
+}
+
+/// A single fragment of a streamed tool call
+#[derive(Debug, Clone, Deserialize)]
+#[cfg(all(feature = "streams", feature = "tools"))]
+pub struct InboundToolCallDelta {
+    /// Index of this call within the response, used to key parallel tool calls
+    pub index: usize,
+    /// The ID of this tool call, present only in the first fragment
+    pub id: Option<String>,
+    /// The function call fragment
+    pub function: InboundFunctionCallDelta,
+}
+
 fn string_or_array<'de, D>(deserializer: D) -> Result<Vec<ChatMessageContent>, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -384,3 +761,78 @@ where
     let buf = Option::<String>::deserialize(deserializer)?;
     Ok(buf.unwrap_or(String::new()))
 }
+
+#[cfg(all(test, feature = "streams", feature = "tools"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_response_chunks_reassembles_parallel_tool_calls() {
+        let chunks = vec![
+            ResponseChunk::BeginResponse {
+                role: Role::Assistant,
+                response_index: 0,
+            },
+            ResponseChunk::FunctionCallDelta {
+                call_index: 0,
+                id: Some("call_1".to_string()),
+                name: Some("get_weather".to_string()),
+                arguments: String::new(),
+                response_index: 0,
+            },
+            ResponseChunk::FunctionCallDelta {
+                call_index: 1,
+                id: Some("call_2".to_string()),
+                name: Some("get_time".to_string()),
+                arguments: String::new(),
+                response_index: 0,
+            },
+            ResponseChunk::FunctionCallDelta {
+                call_index: 0,
+                id: None,
+                name: None,
+                arguments: "{\"city\":".to_string(),
+                response_index: 0,
+            },
+            ResponseChunk::FunctionCallDelta {
+                call_index: 1,
+                id: None,
+                name: None,
+                arguments: "{\"zone\":\"utc\"}".to_string(),
+                response_index: 0,
+            },
+            ResponseChunk::FunctionCallDelta {
+                call_index: 0,
+                id: None,
+                name: None,
+                arguments: "\"nyc\"}".to_string(),
+                response_index: 0,
+            },
+            ResponseChunk::CloseResponse {
+                response_index: 0,
+                finish_reason: Some(FinishReason::ToolCalls),
+            },
+            ResponseChunk::Done,
+        ];
+
+        let messages = ChatMessage::from_response_chunks(chunks);
+        assert_eq!(messages.len(), 1);
+
+        let tool_calls = &messages[0].tool_calls;
+        assert_eq!(tool_calls.len(), 2);
+
+        let get_weather = tool_calls
+            .iter()
+            .find(|call| call.id == "call_1")
+            .expect("first tool call is present");
+        assert_eq!(get_weather.function.name, "get_weather");
+        assert_eq!(get_weather.function.arguments, "{\"city\":\"nyc\"}");
+
+        let get_time = tool_calls
+            .iter()
+            .find(|call| call.id == "call_2")
+            .expect("second tool call is present");
+        assert_eq!(get_time.function.name, "get_time");
+        assert_eq!(get_time.function.arguments, "{\"zone\":\"utc\"}");
+    }
+}